@@ -1,8 +1,29 @@
-//! lib.rs — bun-pty backend (v38: minimal, no background thread)
+//! lib.rs — bun-pty backend (v42: non-blocking writes with backpressure)
 //!
-//! First principles approach: Remove all complexity.
-//! Just do direct non-blocking reads from the PTY fd.
-//! No background thread, no channel, no batching.
+//! Reads are still served through direct non-blocking `read()` calls on the
+//! PTY fd, but consumers no longer have to busy-poll for readiness: every
+//! `Pty`'s `read_fd` is registered with one shared epoll (Linux) / kqueue
+//! (macOS) instance owned by a single background poll thread. Readiness is
+//! reported either through a caller-supplied FFI callback or through a
+//! pollable notify fd, mirroring the send/recv notification split used by
+//! other async RPC layers.
+//!
+//! The master fd itself is held as an `OwnedFd`, obtained safely by
+//! duplicating the fd `MasterPty::as_raw_fd` reports rather than by
+//! reaching into its trait-object layout with a `transmute`.
+//!
+//! Optionally, every chunk read from the PTY is also teed into an
+//! asciinema-style recording so a session can be replayed without the JS
+//! side reassembling timing from individual `bun_pty_read` calls.
+//!
+//! A PTY can also be wired directly to another fd for remote/detached
+//! sessions: the poll thread moves bytes with `splice(2)` where possible,
+//! falling back to a userspace bounce buffer otherwise.
+//!
+//! Writes never block the caller: `bun_pty_write` appends to a bounded
+//! per-PTY queue and the poll thread drains it as the master fd reports
+//! writable, so a child that stops reading its input backs up the queue
+//! instead of stalling the FFI caller.
 
 use portable_pty::{
     native_pty_system, ChildKiller, CommandBuilder, MasterPty, PtySize, SlavePty,
@@ -10,19 +31,19 @@ use portable_pty::{
 use serde::{Deserialize, Serialize};
 use shell_words::split;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     ffi::CStr,
     io::Write,
-    os::raw::{c_char, c_int},
+    os::raw::{c_char, c_int, c_void},
     sync::{
-        atomic::{AtomicBool, AtomicI32, Ordering},
-        Arc, Mutex,
+        atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering},
+        Arc, Mutex, Once,
     },
     thread,
 };
 
 #[cfg(unix)]
-use std::os::unix::io::RawFd;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 
 /* ---------- constants ---------- */
 
@@ -30,6 +51,17 @@ const SUCCESS: c_int = 0;
 const ERROR: c_int = -1;
 const CHILD_EXITED: c_int = -2;
 
+/// Flag bits accepted by `bun_pty_start_recording`.
+const RECORD_COMPRESS: c_int = 1 << 0;
+
+const RECORDING_MAGIC: &[u8; 8] = b"OMXREC01";
+/// High bit of each frame's length field: payload is Snappy-compressed.
+const FRAME_COMPRESSED_BIT: u32 = 1 << 31;
+
+/// Bound on queued-but-unwritten output bytes per `Pty`. Once full,
+/// `bun_pty_write` accepts 0 bytes rather than growing the queue further.
+const WRITE_QUEUE_CAP: usize = 1 << 20; // 1 MiB
+
 /* ---------- helpers ---------- */
 
 fn debug(msg: &str) {
@@ -38,6 +70,21 @@ fn debug(msg: &str) {
     }
 }
 
+/// Gets the raw fd backing `master`. `portable_pty`'s `take_writer()` /
+/// `try_clone_reader()` return boxed `Write`/`Read` trait objects that don't
+/// implement `AsFd`, but `MasterPty` itself exposes the fd directly — no
+/// downcast to a (private) concrete unix type needed.
+#[cfg(unix)]
+fn master_raw_fd(
+    master: &Mutex<Box<dyn MasterPty + Send>>,
+) -> Result<RawFd, Box<dyn std::error::Error + Send + Sync>> {
+    master
+        .lock()
+        .unwrap()
+        .as_raw_fd()
+        .ok_or_else(|| "master PTY has no raw fd".into())
+}
+
 #[cfg(unix)]
 fn set_nonblocking(fd: RawFd) -> bool {
     unsafe {
@@ -49,6 +96,16 @@ fn set_nonblocking(fd: RawFd) -> bool {
     }
 }
 
+/// Appends as much of `data` as fits under `cap` to `queue`, returning the
+/// number of bytes accepted — which may be less than `data.len()`, including
+/// 0 once `queue` is already at `cap`.
+fn enqueue_bounded(queue: &mut VecDeque<u8>, cap: usize, data: &[u8]) -> usize {
+    let room = cap.saturating_sub(queue.len());
+    let accepted = data.len().min(room);
+    queue.extend(&data[..accepted]);
+    accepted
+}
+
 fn parse_env_string(env_ptr: *const c_char) -> HashMap<String, String> {
     if env_ptr.is_null() {
         return HashMap::new();
@@ -97,9 +154,596 @@ impl Command {
     }
 }
 
+/* ---------- readiness poller (epoll / kqueue) ---------- */
+
+/// Signature of the FFI callback passed to `bun_pty_set_data_callback`.
+/// Invoked on the poll thread — callers must not block in it.
+type DataCallback = extern "C" fn(handle: c_int, user_ptr: *mut c_void);
+
+/// Per-`Pty` readiness notification state. A notify fd is always created so
+/// `bun_pty_get_notify_fd` works even if no callback is ever registered; the
+/// callback, when present, is preferred over signalling the fd.
+#[cfg(unix)]
+struct Notify {
+    /// Read end handed out to the consumer via `bun_pty_get_notify_fd`.
+    notify_fd: RawFd,
+    /// Write end the poll thread signals through. Equal to `notify_fd` on
+    /// Linux, where a single `eventfd` serves both directions.
+    signal_fd: RawFd,
+    callback: Mutex<Option<(DataCallback, usize)>>,
+}
+
+#[cfg(unix)]
+impl Notify {
+    fn new() -> std::io::Result<Self> {
+        let (notify_fd, signal_fd) = create_notify_fds()?;
+        Ok(Self {
+            notify_fd,
+            signal_fd,
+            callback: Mutex::new(None),
+        })
+    }
+
+    /// Fire the registered callback, or signal the notify fd exactly once
+    /// until the consumer drains it.
+    fn fire(&self, handle: c_int) {
+        if let Some((cb, user_ptr)) = *self.callback.lock().unwrap() {
+            cb(handle, user_ptr as *mut c_void);
+            return;
+        }
+        signal_notify_fd(self.signal_fd);
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Notify {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.signal_fd);
+            if self.signal_fd != self.notify_fd {
+                libc::close(self.notify_fd);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn create_notify_fds() -> std::io::Result<(RawFd, RawFd)> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((fd, fd))
+}
+
+#[cfg(target_os = "linux")]
+fn signal_notify_fd(fd: RawFd) {
+    let one: u64 = 1;
+    unsafe {
+        libc::write(fd, &one as *const u64 as *const libc::c_void, 8);
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn create_notify_fds() -> std::io::Result<(RawFd, RawFd)> {
+    let mut fds = [0 as RawFd; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    set_nonblocking(fds[0]);
+    set_nonblocking(fds[1]);
+    // [read_end, write_end]
+    Ok((fds[0], fds[1]))
+}
+
+#[cfg(target_os = "macos")]
+fn signal_notify_fd(fd: RawFd) {
+    let byte: u8 = 1;
+    unsafe {
+        // Best-effort: if the pipe is already full of pending wakeups the
+        // consumer hasn't drained yet, there's nothing more to signal.
+        libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+    }
+}
+
+/// What a readiness event is for, packed into the high bits of the
+/// registration tag alongside the handle id (see `encode_event`). Lets one
+/// shared poller dispatch PTY read readiness, a `attach_sink` destination
+/// becoming writable, and an `attach_source` input fd becoming readable,
+/// through the same `epoll`/`kqueue` instance.
+const EVENT_KIND_READ: u64 = 0;
+const EVENT_KIND_SINK_DEST: u64 = 1;
+const EVENT_KIND_SOURCE_SRC: u64 = 2;
+
+fn encode_event(kind: u64, handle: u32) -> u64 {
+    (kind << 32) | handle as u64
+}
+
+fn decode_event(tag: u64) -> (u64, u32) {
+    (tag >> 32, tag as u32)
+}
+
+/// The shared epoll/kqueue fd and the background thread that services it.
+/// Started lazily on first registration so processes that never poll a PTY
+/// never pay for the thread.
+struct Poller {
+    #[cfg(target_os = "linux")]
+    poll_fd: RawFd,
+    #[cfg(target_os = "macos")]
+    poll_fd: RawFd,
+}
+
+#[cfg(target_os = "linux")]
+impl Poller {
+    fn create() -> Self {
+        let poll_fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        assert!(poll_fd >= 0, "epoll_create1 failed");
+        Self { poll_fd }
+    }
+
+    /// Registers a PTY's master fd for both read readiness (new output) and
+    /// write readiness (room to drain the pending-write queue into).
+    fn register(&self, handle: u32, fd: RawFd) {
+        let tag = encode_event(EVENT_KIND_READ, handle);
+        let mut ev = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLET | libc::EPOLLHUP) as u32,
+            u64: tag,
+        };
+        unsafe {
+            libc::epoll_ctl(self.poll_fd, libc::EPOLL_CTL_ADD, fd, &mut ev);
+        }
+    }
+
+    fn register_tagged(&self, tag: u64, fd: RawFd, writable: bool) {
+        let interest = if writable { libc::EPOLLOUT } else { libc::EPOLLIN };
+        let mut ev = libc::epoll_event {
+            events: (interest | libc::EPOLLET | libc::EPOLLHUP) as u32,
+            u64: tag,
+        };
+        unsafe {
+            libc::epoll_ctl(self.poll_fd, libc::EPOLL_CTL_ADD, fd, &mut ev);
+        }
+    }
+
+    fn unregister(&self, fd: RawFd) {
+        unsafe {
+            libc::epoll_ctl(self.poll_fd, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut());
+        }
+    }
+
+    fn run(&self) {
+        let mut events: [libc::epoll_event; 64] = unsafe { std::mem::zeroed() };
+        loop {
+            let n = unsafe {
+                libc::epoll_wait(self.poll_fd, events.as_mut_ptr(), events.len() as c_int, -1)
+            };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                debug(&format!("epoll_wait error: {}", err));
+                continue;
+            }
+            for ev in &events[..n as usize] {
+                let writable = ev.events & (libc::EPOLLOUT as u32) != 0;
+                let readable =
+                    ev.events & ((libc::EPOLLIN | libc::EPOLLHUP | libc::EPOLLERR) as u32) != 0;
+                dispatch_ready(ev.u64, readable, writable);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Poller {
+    fn create() -> Self {
+        let poll_fd = unsafe { libc::kqueue() };
+        assert!(poll_fd >= 0, "kqueue failed");
+        Self { poll_fd }
+    }
+
+    /// Registers a PTY's master fd for both read readiness (new output) and
+    /// write readiness (room to drain the pending-write queue into).
+    fn register(&self, handle: u32, fd: RawFd) {
+        let tag = encode_event(EVENT_KIND_READ, handle);
+        self.register_tagged(tag, fd, false);
+        self.register_tagged(tag, fd, true);
+    }
+
+    fn register_tagged(&self, tag: u64, fd: RawFd, writable: bool) {
+        let filter = if writable { libc::EVFILT_WRITE } else { libc::EVFILT_READ };
+        let kev = libc::kevent {
+            ident: fd as usize,
+            filter,
+            flags: libc::EV_ADD | libc::EV_CLEAR,
+            fflags: 0,
+            data: 0,
+            udata: tag as *mut libc::c_void,
+        };
+        unsafe {
+            libc::kevent(self.poll_fd, &kev, 1, std::ptr::null_mut(), 0, std::ptr::null());
+        }
+    }
+
+    fn unregister(&self, fd: RawFd) {
+        for filter in [libc::EVFILT_READ, libc::EVFILT_WRITE] {
+            let kev = libc::kevent {
+                ident: fd as usize,
+                filter,
+                flags: libc::EV_DELETE,
+                fflags: 0,
+                data: 0,
+                udata: std::ptr::null_mut(),
+            };
+            unsafe {
+                libc::kevent(self.poll_fd, &kev, 1, std::ptr::null_mut(), 0, std::ptr::null());
+            }
+        }
+    }
+
+    fn run(&self) {
+        let mut events: [libc::kevent; 64] = unsafe { std::mem::zeroed() };
+        loop {
+            let n = unsafe {
+                libc::kevent(
+                    self.poll_fd,
+                    std::ptr::null(),
+                    0,
+                    events.as_mut_ptr(),
+                    events.len() as c_int,
+                    std::ptr::null(),
+                )
+            };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                debug(&format!("kevent wait error: {}", err));
+                continue;
+            }
+            for ev in &events[..n as usize] {
+                let writable = ev.filter == libc::EVFILT_WRITE;
+                let readable = ev.filter == libc::EVFILT_READ;
+                dispatch_ready(ev.udata as u64, readable, writable);
+            }
+        }
+    }
+}
+
+/// `readable`/`writable` are independent, not mutually exclusive: edge-
+/// triggered epoll can report both bits set on the same event when a PTY has
+/// both new output and spare write room at once, and dropping either one
+/// here would lose that readiness edge for good.
+#[cfg(unix)]
+fn dispatch_ready(tag: u64, readable: bool, writable: bool) {
+    let (kind, handle) = decode_event(tag);
+    let Some(pty) = REG.lock().unwrap().get(&handle).cloned() else {
+        return;
+    };
+    match kind {
+        EVENT_KIND_READ => {
+            if writable {
+                pty.drain_write_queue();
+                // The master fd is also an `attach_source` forward's dest.
+                pty.pump_source();
+            }
+            if readable {
+                pty.notify.fire(handle as c_int);
+                pty.pump_sink();
+            }
+        }
+        EVENT_KIND_SINK_DEST => pty.pump_sink(),
+        EVENT_KIND_SOURCE_SRC => pty.pump_source(),
+        _ => {}
+    }
+}
+
+#[cfg(unix)]
+lazy_static::lazy_static! {
+    static ref POLLER: Poller = Poller::create();
+}
+
+#[cfg(unix)]
+static POLLER_START: Once = Once::new();
+
+#[cfg(unix)]
+fn ensure_poller_started() {
+    POLLER_START.call_once(|| {
+        thread::spawn(|| POLLER.run());
+    });
+}
+
+/* ---------- session recording ---------- */
+
+/// Tees PTY output to a length-prefixed frame stream:
+/// `[u64 monotonic_micros][u32 byte_len][payload]`, so playback can
+/// reproduce the original timing. `byte_len`'s high bit marks a
+/// Snappy-compressed payload (see `FRAME_COMPRESSED_BIT`).
+struct Recorder {
+    writer: Mutex<std::io::BufWriter<std::fs::File>>,
+    start: std::time::Instant,
+    compress: bool,
+}
+
+impl Recorder {
+    fn create(path: &str, compress: bool) -> std::io::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        writer.write_all(RECORDING_MAGIC)?;
+        writer.write_all(&[compress as u8])?;
+        Ok(Self {
+            writer: Mutex::new(writer),
+            start: std::time::Instant::now(),
+            compress,
+        })
+    }
+
+    /// Append one frame. Best-effort: a compression failure is logged and
+    /// the frame dropped, and write errors are ignored; neither is
+    /// propagated to the read path.
+    fn record(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let micros = self.start.elapsed().as_micros() as u64;
+
+        // Compress before taking the writer lock, so the lock is only ever
+        // held across a single frame's worth of appends, not the encode.
+        if self.compress {
+            let mut buf = vec![0u8; snap::raw::max_compress_len(data.len())];
+            match snap::raw::Encoder::new().compress(data, &mut buf) {
+                Ok(n) => {
+                    let len = n as u32 | FRAME_COMPRESSED_BIT;
+                    let mut writer = self.writer.lock().unwrap();
+                    let _ = writer.write_all(&micros.to_le_bytes());
+                    let _ = writer.write_all(&len.to_le_bytes());
+                    let _ = writer.write_all(&buf[..n]);
+                }
+                Err(e) => debug(&format!("recording compress error: {}", e)),
+            }
+        } else {
+            let mut writer = self.writer.lock().unwrap();
+            let _ = writer.write_all(&micros.to_le_bytes());
+            let _ = writer.write_all(&(data.len() as u32).to_le_bytes());
+            let _ = writer.write_all(data);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/* ---------- zero-copy forwarding (splice) ---------- */
+
+/// Result of one `splice(2)` attempt between two fds.
+#[cfg(target_os = "linux")]
+enum SpliceOutcome {
+    Moved(usize),
+    WouldBlock,
+    Unsupported,
+    Error,
+}
+
+#[cfg(target_os = "linux")]
+fn splice_once(from: RawFd, to: RawFd, len: usize) -> SpliceOutcome {
+    let n = unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    };
+    if n >= 0 {
+        return SpliceOutcome::Moved(n as usize);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EAGAIN) => SpliceOutcome::WouldBlock,
+        Some(libc::EINVAL) | Some(libc::ENOSYS) => SpliceOutcome::Unsupported,
+        _ => {
+            debug(&format!("splice error: {}", err));
+            SpliceOutcome::Error
+        }
+    }
+}
+
+const SPLICE_CHUNK: usize = 64 * 1024;
+
+/// Transfer state for one direction of a `bun_pty_attach_sink` /
+/// `bun_pty_attach_source` forward. Starts in `Splice` mode on Linux and
+/// downgrades permanently to `Bounce` the first time `splice` reports the
+/// fds aren't splice-eligible (or on platforms without `splice` at all).
+enum Transfer {
+    #[cfg(target_os = "linux")]
+    Splice {
+        pipe_r: OwnedFd,
+        pipe_w: OwnedFd,
+        /// Bytes already known to be sitting in the intermediate pipe.
+        /// Only drain-first when this is nonzero — otherwise the pipe is
+        /// empty on this call and draining it first would misreport "dest
+        /// backed up" for what's actually just "nothing staged yet".
+        pending: usize,
+    },
+    Bounce { residual: Vec<u8> },
+}
+
+#[cfg(unix)]
+struct Forward {
+    peer_fd: OwnedFd,
+    state: Mutex<Transfer>,
+}
+
+#[cfg(unix)]
+impl Forward {
+    fn new(peer_fd: OwnedFd) -> std::io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        let state = {
+            let mut fds = [0 as RawFd; 2];
+            if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Mutex::new(Transfer::Splice {
+                pipe_r: unsafe { OwnedFd::from_raw_fd(fds[0]) },
+                pipe_w: unsafe { OwnedFd::from_raw_fd(fds[1]) },
+                pending: 0,
+            })
+        };
+        #[cfg(not(target_os = "linux"))]
+        let state = Mutex::new(Transfer::Bounce { residual: Vec::new() });
+
+        Ok(Self { peer_fd, state })
+    }
+
+    /// Move whatever is currently readable on `src_fd` to `self.peer_fd`,
+    /// stopping (without losing data) the moment either side would block.
+    fn pump(&self, src_fd: RawFd) {
+        let mut state = self.state.lock().unwrap();
+        #[cfg(target_os = "linux")]
+        {
+            if let Transfer::Splice { pipe_r, pipe_w, pending } = &mut *state {
+                let pipe_r = pipe_r.as_raw_fd();
+                let pipe_w = pipe_w.as_raw_fd();
+                match self.pump_splice(src_fd, pipe_r, pipe_w, pending) {
+                    Ok(()) => return,
+                    Err(()) => {
+                        debug("splice unsupported for this fd pair, falling back to read/write");
+                        *state = Transfer::Bounce { residual: Vec::new() };
+                    }
+                }
+            }
+        }
+        if let Transfer::Bounce { residual } = &mut *state {
+            self.pump_bounce(src_fd, residual);
+        }
+    }
+
+    /// `pending` tracks bytes already known to be staged in `pipe_r`/`pipe_w`
+    /// across calls. Only drain-first when it's nonzero: on the first call
+    /// (or any call where the prior one fully drained the pipe) the pipe is
+    /// genuinely empty, and draining it first would report "dest backed up"
+    /// (`WouldBlock` on an empty pipe looks identical to a full dest) before
+    /// ever attempting the `src_fd -> pipe_w` splice that would feed it.
+    #[cfg(target_os = "linux")]
+    fn pump_splice(
+        &self,
+        src_fd: RawFd,
+        pipe_r: RawFd,
+        pipe_w: RawFd,
+        pending: &mut usize,
+    ) -> Result<(), ()> {
+        let dest_fd = self.peer_fd.as_raw_fd();
+
+        if *pending > 0 && !self.drain_pipe(pipe_r, dest_fd, pending)? {
+            return Ok(()); // dest would block; resume on its next readiness event
+        }
+
+        loop {
+            match splice_once(src_fd, pipe_w, SPLICE_CHUNK) {
+                SpliceOutcome::Moved(0) => return Ok(()), // src drained
+                SpliceOutcome::Moved(n) => {
+                    *pending += n;
+                    if !self.drain_pipe(pipe_r, dest_fd, pending)? {
+                        return Ok(());
+                    }
+                }
+                SpliceOutcome::WouldBlock => return Ok(()),
+                SpliceOutcome::Unsupported => return Err(()),
+                SpliceOutcome::Error => return Ok(()),
+            }
+        }
+    }
+
+    /// Drain the intermediate pipe into `dest_fd`, decrementing `pending` as
+    /// bytes move. Returns `Ok(true)` once the pipe is empty, `Ok(false)` if
+    /// `dest_fd` would block (bytes are left sitting in the pipe for the
+    /// next readiness event).
+    #[cfg(target_os = "linux")]
+    fn drain_pipe(&self, pipe_r: RawFd, dest_fd: RawFd, pending: &mut usize) -> Result<bool, ()> {
+        loop {
+            match splice_once(pipe_r, dest_fd, SPLICE_CHUNK) {
+                SpliceOutcome::Moved(0) => return Ok(true),
+                SpliceOutcome::Moved(n) => {
+                    *pending = pending.saturating_sub(n);
+                    // An empty pipe reports `WouldBlock`, not `Moved(0)`, so
+                    // stop on the byte count instead.
+                    if *pending == 0 {
+                        return Ok(true);
+                    }
+                    continue;
+                }
+                SpliceOutcome::WouldBlock => return Ok(false),
+                SpliceOutcome::Unsupported => return Err(()),
+                SpliceOutcome::Error => {
+                    *pending = 0;
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    fn pump_bounce(&self, src_fd: RawFd, residual: &mut Vec<u8>) {
+        let dest_fd = self.peer_fd.as_raw_fd();
+
+        if !Self::flush_residual(dest_fd, residual) {
+            return; // dest still backed up; resume next time we're polled
+        }
+
+        let mut buf = [0u8; SPLICE_CHUNK];
+        loop {
+            let n = unsafe {
+                libc::read(src_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            };
+            if n <= 0 {
+                break;
+            }
+            residual.extend_from_slice(&buf[..n as usize]);
+            if !Self::flush_residual(dest_fd, residual) {
+                break;
+            }
+        }
+    }
+
+    /// Write as much of `residual` to `dest_fd` as it will accept. Returns
+    /// `true` once `residual` is fully flushed.
+    fn flush_residual(dest_fd: RawFd, residual: &mut Vec<u8>) -> bool {
+        while !residual.is_empty() {
+            let n = unsafe {
+                libc::write(
+                    dest_fd,
+                    residual.as_ptr() as *const libc::c_void,
+                    residual.len(),
+                )
+            };
+            if n <= 0 {
+                return false;
+            }
+            residual.drain(..n as usize);
+        }
+        true
+    }
+}
+
 /* ---------- PTY handle ---------- */
 
 struct Pty {
+    // Only the non-unix `write()` path still calls through this; unix writes
+    // go through `write_fd` (an independent dup of the master fd) and the
+    // queue below instead.
+    #[cfg(not(unix))]
     writer: Arc<Mutex<Box<dyn Write + Send>>>,
     _slave: Box<dyn SlavePty + Send>,
     _master: Arc<Mutex<Box<dyn MasterPty + Send>>>,
@@ -107,9 +751,34 @@ struct Pty {
     exited: AtomicBool,
     exit_code: AtomicI32,
     pid: c_int,
-    // File descriptor for direct reads
+    // Handle id assigned at registration time, used to address callbacks.
+    id: AtomicU32,
+    // Master fd for direct reads, owned outright so `Drop` closes it once.
+    #[cfg(unix)]
+    read_fd: OwnedFd,
     #[cfg(unix)]
-    read_fd: RawFd,
+    notify: Notify,
+    recorder: Mutex<Option<Arc<Recorder>>>,
+    // PTY read_fd -> external fd, driven by the poll thread on read readiness.
+    #[cfg(unix)]
+    sink: Mutex<Option<Arc<Forward>>>,
+    // External fd -> PTY, driven by the poll thread on the source's own readiness.
+    #[cfg(unix)]
+    source: Mutex<Option<SourceBinding>>,
+    // Independent dup of the master fd, kept non-blocking; the poll thread
+    // drains `write_queue` into it as it reports writable.
+    #[cfg(unix)]
+    write_fd: OwnedFd,
+    #[cfg(unix)]
+    write_queue: Mutex<VecDeque<u8>>,
+}
+
+/// An `attach_source` forward also needs to remember the external fd it
+/// reads from, since `Forward` only owns the fd it writes *to*.
+#[cfg(unix)]
+struct SourceBinding {
+    fwd: Arc<Forward>,
+    src_fd: OwnedFd,
 }
 
 unsafe impl Send for Pty {}
@@ -124,37 +793,50 @@ impl Pty {
         let pid = child.process_id().map(|p| p as c_int).unwrap_or(ERROR);
 
         let master = Arc::new(Mutex::new(pair.master));
+        #[cfg(not(unix))]
         let writer = Arc::new(Mutex::new(master.lock().unwrap().take_writer()?));
 
-        // Get fd for direct reads
+        // Each of `write_fd`/`read_fd` gets its own independent dup of the
+        // master's fd (via `master_raw_fd`, not the boxed writer/reader
+        // above) so they can be closed on their own. `O_NONBLOCK` lives on
+        // the open file description all dups share, so setting it below
+        // makes the master's own fd non-blocking too.
         #[cfg(unix)]
-        let read_fd = {
-            let reader = master.lock().unwrap().try_clone_reader()?;
-            use std::io::Read;
-            let rdr_ref: &dyn Read = &*reader;
-            let extracted_fd: i32 = unsafe {
-                let (data_ptr, _vtable): (*const u8, *const u8) =
-                    std::mem::transmute(rdr_ref);
-                *(data_ptr as *const i32)
-            };
+        let write_fd: OwnedFd = {
+            let raw = master_raw_fd(&master)?;
+            let dup = unsafe { libc::dup(raw) };
+            if dup < 0 {
+                return Err("Failed to dup PTY master fd for writing".into());
+            }
+            let owned = unsafe { OwnedFd::from_raw_fd(dup) };
+            if !set_nonblocking(owned.as_raw_fd()) {
+                return Err("Failed to set writer non-blocking".into());
+            }
+            owned
+        };
 
-            // Dup the fd so it survives reader drop
-            let dup_fd = unsafe { libc::dup(extracted_fd) };
-            if dup_fd < 0 {
-                return Err("Failed to dup fd".into());
+        #[cfg(unix)]
+        let read_fd: OwnedFd = {
+            let raw = master_raw_fd(&master)?;
+            let dup = unsafe { libc::dup(raw) };
+            if dup < 0 {
+                return Err("Failed to dup PTY master fd for reading".into());
             }
+            let owned = unsafe { OwnedFd::from_raw_fd(dup) };
 
-            // Set non-blocking
-            if !set_nonblocking(dup_fd) {
-                unsafe { libc::close(dup_fd) };
+            if !set_nonblocking(owned.as_raw_fd()) {
                 return Err("Failed to set non-blocking".into());
             }
 
-            debug(&format!("PTY fd={} set to non-blocking", dup_fd));
-            dup_fd
+            debug(&format!("PTY fd={} set to non-blocking", owned.as_raw_fd()));
+            owned
         };
 
+        #[cfg(unix)]
+        let notify = Notify::new()?;
+
         let pty = Arc::new(Self {
+            #[cfg(not(unix))]
             writer,
             _slave: pair.slave,
             _master: master,
@@ -162,8 +844,20 @@ impl Pty {
             exited: AtomicBool::new(false),
             exit_code: AtomicI32::new(-1),
             pid,
+            id: AtomicU32::new(0),
             #[cfg(unix)]
             read_fd,
+            #[cfg(unix)]
+            notify,
+            recorder: Mutex::new(None),
+            #[cfg(unix)]
+            sink: Mutex::new(None),
+            #[cfg(unix)]
+            write_fd,
+            #[cfg(unix)]
+            write_queue: Mutex::new(VecDeque::new()),
+            #[cfg(unix)]
+            source: Mutex::new(None),
         });
 
         // Spawn wait thread for child exit
@@ -178,17 +872,128 @@ impl Pty {
                 }
                 pty_clone.exited.store(true, Ordering::SeqCst);
                 debug("Child process exited");
+                // Drop (and thereby flush) any active recording now rather
+                // than waiting for the `Pty` itself to be dropped.
+                pty_clone.recorder.lock().unwrap().take();
+                // The read fd also gets EPOLLHUP once the child side of the
+                // PTY closes, but fire here too so `exit_code` is observable
+                // even if the kernel hasn't delivered that yet.
+                #[cfg(unix)]
+                pty_clone.notify.fire(pty_clone.id.load(Ordering::SeqCst) as c_int);
             });
         }
 
         Ok(pty)
     }
 
+    /// Finish wiring the handle up to the shared poller. Called once the
+    /// registry has assigned this `Pty` its handle id.
     #[cfg(unix)]
-    fn read_available(&self, out_buf: &mut [u8]) -> c_int {
-        if self.exited.load(Ordering::SeqCst) {
-            return CHILD_EXITED;
+    fn register_with_poller(&self, id: u32) {
+        self.id.store(id, Ordering::SeqCst);
+        ensure_poller_started();
+        POLLER.register(id, self.read_fd.as_raw_fd());
+    }
+
+    /// Wire this PTY's output straight to `dest_fd` on the poll thread,
+    /// bypassing `bun_pty_read`/JS entirely. Mutually exclusive with an
+    /// in-progress recording: `splice(2)` moves bytes without ever landing
+    /// them in a userspace buffer `Recorder::record` could tee from, so the
+    /// two would otherwise silently race over the same output.
+    #[cfg(unix)]
+    fn attach_sink(&self, dest_fd: RawFd) -> c_int {
+        if self.recorder.lock().unwrap().is_some() {
+            debug("attach_sink error: a recording is already in progress for this handle");
+            return ERROR;
+        }
+        let dup = unsafe { libc::dup(dest_fd) };
+        if dup < 0 || !set_nonblocking(dup) {
+            if dup >= 0 {
+                unsafe { libc::close(dup) };
+            }
+            return ERROR;
+        }
+        let owned = unsafe { OwnedFd::from_raw_fd(dup) };
+        let raw = owned.as_raw_fd();
+
+        let fwd = match Forward::new(owned) {
+            Ok(fwd) => Arc::new(fwd),
+            Err(e) => {
+                debug(&format!("attach_sink error: {}", e));
+                return ERROR;
+            }
+        };
+
+        *self.sink.lock().unwrap() = Some(fwd);
+        POLLER.register_tagged(
+            encode_event(EVENT_KIND_SINK_DEST, self.id.load(Ordering::SeqCst)),
+            raw,
+            true,
+        );
+        self.pump_sink();
+        SUCCESS
+    }
+
+    /// Wire `src_fd` straight into this PTY's input on the poll thread.
+    #[cfg(unix)]
+    fn attach_source(&self, src_fd: RawFd) -> c_int {
+        let dup = unsafe { libc::dup(src_fd) };
+        if dup < 0 || !set_nonblocking(dup) {
+            if dup >= 0 {
+                unsafe { libc::close(dup) };
+            }
+            return ERROR;
+        }
+        let owned_src = unsafe { OwnedFd::from_raw_fd(dup) };
+        let raw_src = owned_src.as_raw_fd();
+
+        // The PTY master fd is full-duplex, so the same `read_fd` we dup'd
+        // for reading also accepts writes that become the child's input.
+        let write_dup = unsafe { libc::dup(self.read_fd.as_raw_fd()) };
+        if write_dup < 0 {
+            return ERROR;
+        }
+        let write_fd = unsafe { OwnedFd::from_raw_fd(write_dup) };
+
+        let fwd = match Forward::new(write_fd) {
+            Ok(fwd) => Arc::new(fwd),
+            Err(e) => {
+                debug(&format!("attach_source error: {}", e));
+                return ERROR;
+            }
+        };
+
+        *self.source.lock().unwrap() = Some(SourceBinding { fwd, src_fd: owned_src });
+        POLLER.register_tagged(
+            encode_event(EVENT_KIND_SOURCE_SRC, self.id.load(Ordering::SeqCst)),
+            raw_src,
+            false,
+        );
+        self.pump_source();
+        SUCCESS
+    }
+
+    #[cfg(unix)]
+    fn pump_sink(&self) {
+        if let Some(fwd) = self.sink.lock().unwrap().clone() {
+            fwd.pump(self.read_fd.as_raw_fd());
         }
+    }
+
+    #[cfg(unix)]
+    fn pump_source(&self) {
+        if let Some(binding) = self.source.lock().unwrap().as_ref() {
+            binding.fwd.pump(binding.src_fd.as_raw_fd());
+        }
+    }
+
+    #[cfg(unix)]
+    fn read_available(&self, out_buf: &mut [u8]) -> c_int {
+        // Don't trust `exited` up front: the wait thread can flip it before
+        // the final bytes the child wrote are actually drained from the PTY,
+        // since that flag and the kernel's read buffer are updated
+        // independently. Always attempt the read loop first and only report
+        // `CHILD_EXITED` once it comes up genuinely empty.
 
         // Drain all available data in one FFI call (reduces round-trips)
         let mut total = 0usize;
@@ -201,7 +1006,7 @@ impl Pty {
 
             let n = unsafe {
                 libc::read(
-                    self.read_fd,
+                    self.read_fd.as_raw_fd(),
                     remaining.as_mut_ptr() as *mut libc::c_void,
                     remaining.len(),
                 )
@@ -233,6 +1038,9 @@ impl Pty {
         }
 
         if total > 0 {
+            if let Some(rec) = self.recorder.lock().unwrap().as_ref() {
+                rec.record(&out_buf[..total]);
+            }
             total as c_int
         } else if self.exited.load(Ordering::SeqCst) {
             CHILD_EXITED
@@ -241,6 +1049,22 @@ impl Pty {
         }
     }
 
+    /// Appends as much of `data` as fits under `WRITE_QUEUE_CAP` to the
+    /// output queue and returns the number of bytes accepted — which may be
+    /// less than `data.len()`, including 0 when the queue is already full.
+    /// Never blocks: a child that stops draining its input just backs up
+    /// the queue instead of stalling the caller.
+    #[cfg(unix)]
+    fn write(&self, data: &[u8]) -> c_int {
+        if self.exited.load(Ordering::SeqCst) {
+            return CHILD_EXITED;
+        }
+        let accepted = enqueue_bounded(&mut self.write_queue.lock().unwrap(), WRITE_QUEUE_CAP, data);
+        self.drain_write_queue();
+        accepted as c_int
+    }
+
+    #[cfg(not(unix))]
     fn write(&self, data: &[u8]) -> c_int {
         if self.exited.load(Ordering::SeqCst) {
             return CHILD_EXITED;
@@ -254,6 +1078,55 @@ impl Pty {
         }
     }
 
+    /// Write as much of the queue as `write_fd` will currently accept.
+    /// Called from the poll thread on write readiness and right after
+    /// `write()` enqueues, so a queue that never blocks drains immediately.
+    #[cfg(unix)]
+    fn drain_write_queue(&self) {
+        let mut queue = self.write_queue.lock().unwrap();
+        while !queue.is_empty() {
+            let (front, _) = queue.as_slices();
+            let n = unsafe {
+                libc::write(
+                    self.write_fd.as_raw_fd(),
+                    front.as_ptr() as *const libc::c_void,
+                    front.len(),
+                )
+            };
+            if n > 0 {
+                queue.drain(..n as usize);
+                continue;
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::WouldBlock {
+                debug(&format!("write error: {}", err));
+                queue.clear();
+            }
+            break;
+        }
+    }
+
+    /// Bytes currently queued but not yet accepted by `write_fd` — lets the
+    /// caller apply flow control instead of overrunning `WRITE_QUEUE_CAP`.
+    #[cfg(unix)]
+    fn write_pending(&self) -> c_int {
+        self.write_queue.lock().unwrap().len() as c_int
+    }
+
+    /// Give the child a grace period to drain queued input before the
+    /// handle is torn down, rather than blocking `bun_pty_close` forever.
+    #[cfg(unix)]
+    fn flush_write_queue_blocking(&self) {
+        for _ in 0..50 {
+            self.drain_write_queue();
+            if self.write_queue.lock().unwrap().is_empty() {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        debug("close: dropping unflushed queued writes after timeout");
+    }
+
     fn resize(&self, size: PtySize) -> c_int {
         if let Err(e) = self._master.lock().unwrap().resize(size) {
             debug(&format!("Resize error: {}", e));
@@ -273,9 +1146,17 @@ impl Pty {
 
 impl Drop for Pty {
     fn drop(&mut self) {
+        // The owned fds close themselves once this body returns; just
+        // unregister them from the poller first so no stale event fires.
         #[cfg(unix)]
-        unsafe {
-            libc::close(self.read_fd);
+        {
+            POLLER.unregister(self.read_fd.as_raw_fd());
+            if let Some(fwd) = self.sink.lock().unwrap().take() {
+                POLLER.unregister(fwd.peer_fd.as_raw_fd());
+            }
+            if let Some(binding) = self.source.lock().unwrap().take() {
+                POLLER.unregister(binding.src_fd.as_raw_fd());
+            }
         }
     }
 }
@@ -289,6 +1170,8 @@ lazy_static::lazy_static! {
 
 fn insert(p: Arc<Pty>) -> u32 {
     let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) as u32;
+    #[cfg(unix)]
+    p.register_with_poller(id);
     REG.lock().unwrap().insert(id, p);
     id
 }
@@ -354,6 +1237,24 @@ pub unsafe extern "C" fn bun_pty_write(handle: c_int, data: *const u8, len: c_in
     with(handle as u32, |p| p.write(slice))
 }
 
+/// Bytes queued by `bun_pty_write` but not yet accepted by the PTY, so the
+/// caller can apply flow control before writes start being rejected at
+/// `WRITE_QUEUE_CAP`.
+#[unsafe(no_mangle)]
+pub extern "C" fn bun_pty_write_pending(handle: c_int) -> c_int {
+    if handle <= 0 {
+        return ERROR;
+    }
+    #[cfg(unix)]
+    {
+        with(handle as u32, |p| p.write_pending())
+    }
+    #[cfg(not(unix))]
+    {
+        ERROR
+    }
+}
+
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn bun_pty_read(handle: c_int, buf: *mut u8, len: c_int) -> c_int {
     if handle <= 0 || buf.is_null() || len <= 0 {
@@ -372,6 +1273,78 @@ pub unsafe extern "C" fn bun_pty_read(handle: c_int, buf: *mut u8, len: c_int) -
     }
 }
 
+/// Register a callback the poll thread invokes (exactly once per readiness
+/// transition) when `handle`'s PTY becomes readable or the child exits.
+/// Passing a null `callback` clears it, falling back to the notify fd.
+#[unsafe(no_mangle)]
+pub extern "C" fn bun_pty_set_data_callback(
+    handle: c_int,
+    callback: Option<DataCallback>,
+    user_ptr: *mut c_void,
+) -> c_int {
+    if handle <= 0 {
+        return ERROR;
+    }
+    #[cfg(unix)]
+    {
+        with(handle as u32, |p| {
+            *p.notify.callback.lock().unwrap() = callback.map(|cb| (cb, user_ptr as usize));
+            SUCCESS
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        ERROR
+    }
+}
+
+/// Returns the PTY master's raw fd, borrowed — the `Pty` retains ownership
+/// and still closes it on `bun_pty_close`/drop. Lets external event loops
+/// (libuv, tokio) poll it directly instead of going through `bun_pty_read`.
+#[unsafe(no_mangle)]
+pub extern "C" fn bun_pty_get_fd(handle: c_int) -> c_int {
+    if handle <= 0 {
+        return ERROR;
+    }
+    #[cfg(unix)]
+    {
+        // Bypass `with()`: its `unwrap_or_default()` would return fd 0 for a
+        // missing handle, indistinguishable from a real fd 0 (stdin).
+        REG.lock()
+            .unwrap()
+            .get(&(handle as u32))
+            .map(|p| p.read_fd.as_raw_fd())
+            .unwrap_or(ERROR)
+    }
+    #[cfg(not(unix))]
+    {
+        ERROR
+    }
+}
+
+/// Returns an fd the caller can poll/await in its own event loop: readable
+/// exactly when data is available or the child has exited.
+#[unsafe(no_mangle)]
+pub extern "C" fn bun_pty_get_notify_fd(handle: c_int) -> c_int {
+    if handle <= 0 {
+        return ERROR;
+    }
+    #[cfg(unix)]
+    {
+        // Bypass `with()`: its `unwrap_or_default()` would return fd 0 for a
+        // missing handle, indistinguishable from a real fd 0 (stdin).
+        REG.lock()
+            .unwrap()
+            .get(&(handle as u32))
+            .map(|p| p.notify.notify_fd)
+            .unwrap_or(ERROR)
+    }
+    #[cfg(not(unix))]
+    {
+        ERROR
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn bun_pty_resize(handle: c_int, cols: c_int, rows: c_int) -> c_int {
     if handle <= 0 || cols <= 0 || rows <= 0 {
@@ -411,10 +1384,197 @@ pub extern "C" fn bun_pty_get_exit_code(handle: c_int) -> c_int {
     with(handle as u32, |p| p.exit_code.load(Ordering::SeqCst))
 }
 
+/// Start teeing every chunk read from `handle` into `path` as a
+/// length-prefixed frame stream. Pass `RECORD_COMPRESS` in `flags` to
+/// Snappy-compress each frame body. Replaces any recording already in
+/// progress for this handle. Fails if a sink is attached (see
+/// `bun_pty_attach_sink`): `splice(2)` moves bytes without ever landing them
+/// in a userspace buffer a recording could tee from.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bun_pty_start_recording(
+    handle: c_int,
+    path: *const c_char,
+    flags: c_int,
+) -> c_int {
+    if handle <= 0 || path.is_null() {
+        return ERROR;
+    }
+    #[cfg(unix)]
+    if with(handle as u32, |p| p.sink.lock().unwrap().is_some()) {
+        debug("start_recording error: a sink is already attached to this handle");
+        return ERROR;
+    }
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    let compress = flags & RECORD_COMPRESS != 0;
+
+    match Recorder::create(&path, compress) {
+        Ok(rec) => with(handle as u32, |p| {
+            *p.recorder.lock().unwrap() = Some(Arc::new(rec));
+            SUCCESS
+        }),
+        Err(e) => {
+            debug(&format!("start_recording error: {}", e));
+            ERROR
+        }
+    }
+}
+
+/// Stop any in-progress recording for `handle`, flushing and closing it.
+#[unsafe(no_mangle)]
+pub extern "C" fn bun_pty_stop_recording(handle: c_int) -> c_int {
+    if handle <= 0 {
+        return ERROR;
+    }
+    with(handle as u32, |p| {
+        p.recorder.lock().unwrap().take();
+        SUCCESS
+    })
+}
+
+/// Forward this PTY's output straight to `dest_fd` (a socket or pipe) via
+/// `splice(2)` where the fds allow it (a userspace read/write copy
+/// otherwise), without bouncing the data through JS.
+/// Replaces any sink already attached to this handle. Fails if a recording
+/// is in progress (see `bun_pty_start_recording`).
+#[unsafe(no_mangle)]
+pub extern "C" fn bun_pty_attach_sink(handle: c_int, dest_fd: c_int) -> c_int {
+    if handle <= 0 || dest_fd < 0 {
+        return ERROR;
+    }
+    #[cfg(unix)]
+    {
+        with(handle as u32, |p| p.attach_sink(dest_fd))
+    }
+    #[cfg(not(unix))]
+    {
+        ERROR
+    }
+}
+
+/// Forward `src_fd` straight into this PTY's input via `splice(2)` where the
+/// fds allow it (a userspace read/write copy otherwise). Replaces any source
+/// already attached to this handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn bun_pty_attach_source(handle: c_int, src_fd: c_int) -> c_int {
+    if handle <= 0 || src_fd < 0 {
+        return ERROR;
+    }
+    #[cfg(unix)]
+    {
+        with(handle as u32, |p| p.attach_source(src_fd))
+    }
+    #[cfg(not(unix))]
+    {
+        ERROR
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn bun_pty_close(handle: c_int) {
     if handle <= 0 {
         return;
     }
+    #[cfg(unix)]
+    with(handle as u32, |p| {
+        p.flush_write_queue_blocking();
+    });
     REG.lock().unwrap().remove(&(handle as u32));
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Regression test for the `pump_splice`/`drain_pipe` ordering bug: the
+    /// very first `pump()` call sees an empty intermediate pipe, which must
+    /// not be mistaken for "dest backed up" before the src fd is ever read.
+    #[test]
+    fn forward_pump_moves_bytes_end_to_end() {
+        let mut src_fds = [0 as RawFd; 2];
+        let mut dest_fds = [0 as RawFd; 2];
+        unsafe {
+            assert_eq!(libc::pipe(src_fds.as_mut_ptr()), 0);
+            assert_eq!(libc::pipe(dest_fds.as_mut_ptr()), 0);
+        }
+        let (src_r, src_w) = (src_fds[0], src_fds[1]);
+        let (dest_r, dest_w) = (dest_fds[0], dest_fds[1]);
+        assert!(set_nonblocking(src_r));
+        assert!(set_nonblocking(dest_w));
+
+        let peer = unsafe { OwnedFd::from_raw_fd(dest_w) };
+        let fwd = Forward::new(peer).expect("Forward::new");
+
+        let payload = b"hello from the source fd";
+        unsafe {
+            libc::write(src_w, payload.as_ptr() as *const libc::c_void, payload.len());
+        }
+
+        fwd.pump(src_r);
+
+        let mut buf = [0u8; 64];
+        let n = unsafe { libc::read(dest_r, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        assert!(n > 0, "expected bytes forwarded to dest, read() returned {}", n);
+        assert_eq!(&buf[..n as usize], &payload[..]);
+
+        unsafe {
+            libc::close(src_r);
+            libc::close(src_w);
+            libc::close(dest_r);
+        }
+    }
+
+    #[test]
+    fn recorder_frame_roundtrip_uncompressed() {
+        let path = std::env::temp_dir().join(format!("openmux_test_rec_u_{}.rec", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        {
+            let rec = Recorder::create(path_str, false).expect("Recorder::create");
+            rec.record(b"hello");
+            rec.flush();
+        }
+        let bytes = std::fs::read(&path).expect("read recording");
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(&bytes[..8], RECORDING_MAGIC);
+        assert_eq!(bytes[8], 0); // compress flag
+        let len = u32::from_le_bytes(bytes[17..21].try_into().unwrap());
+        assert_eq!(len & FRAME_COMPRESSED_BIT, 0);
+        assert_eq!(len as usize, 5);
+        assert_eq!(&bytes[21..26], b"hello");
+    }
+
+    #[test]
+    fn recorder_frame_roundtrip_compressed() {
+        let path = std::env::temp_dir().join(format!("openmux_test_rec_c_{}.rec", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let payload = vec![b'x'; 256];
+        {
+            let rec = Recorder::create(path_str, true).expect("Recorder::create");
+            rec.record(&payload);
+            rec.flush();
+        }
+        let bytes = std::fs::read(&path).expect("read recording");
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(&bytes[..8], RECORDING_MAGIC);
+        assert_eq!(bytes[8], 1);
+        let len_field = u32::from_le_bytes(bytes[17..21].try_into().unwrap());
+        assert_ne!(len_field & FRAME_COMPRESSED_BIT, 0);
+        let len = (len_field & !FRAME_COMPRESSED_BIT) as usize;
+        let compressed = &bytes[21..21 + len];
+        let mut decoded = vec![0u8; snap::raw::decompress_len(compressed).unwrap()];
+        let n = snap::raw::Decoder::new()
+            .decompress(compressed, &mut decoded)
+            .unwrap();
+        assert_eq!(&decoded[..n], &payload[..]);
+    }
+
+    #[test]
+    fn enqueue_bounded_rejects_past_cap() {
+        let mut queue = VecDeque::new();
+        assert_eq!(enqueue_bounded(&mut queue, 4, b"ab"), 2);
+        assert_eq!(enqueue_bounded(&mut queue, 4, b"cd"), 2);
+        assert_eq!(enqueue_bounded(&mut queue, 4, b"ef"), 0);
+        assert_eq!(queue, VecDeque::from(vec![b'a', b'b', b'c', b'd']));
+    }
+}